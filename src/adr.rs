@@ -0,0 +1,156 @@
+//! Adaptive Data Rate: uplink-side back-off state machine plus application
+//! of server-issued `LinkADRReq` commands.
+//!
+//! Until now the device always transmitted at the region's default data
+//! rate. This tracks the standard LoRaWAN ADR_ACK_CNT/ADR_ACK_DELAY back-off
+//! (TS001 §4.3.1.1) so a device that stops hearing from the network steps
+//! itself down to the most robust setting, and applies `LinkADRReq` when the
+//! network *does* hear from it.
+
+/// Uplinks sent without a downlink before the device sets ADRACKReq.
+const ADR_ACK_LIMIT: u16 = 64;
+/// Further uplinks sent with ADRACKReq set, still with no downlink, before
+/// the device steps its data rate down.
+const ADR_ACK_DELAY: u16 = 32;
+
+/// EU433's most robust data rate (SF12, 125 kHz).
+const MIN_DATA_RATE: u8 = 0;
+/// EU433 DR0..DR5 (SF12..SF7 @ 125 kHz).
+const MAX_DATA_RATE: u8 = 5;
+
+/// Outcome of applying a `LinkADRReq`, used to build the matching
+/// `LinkADRAns` status bits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkAdrResult {
+    pub power_ok: bool,
+    pub data_rate_ok: bool,
+    pub channel_mask_ok: bool,
+}
+
+pub struct AdrState {
+    /// Whether the device has the ADR bit set (network-controlled link
+    /// adaptation enabled).
+    pub enabled: bool,
+    pub data_rate: u8,
+    pub tx_power_index: u8,
+    /// One bit per channel; EU433 only has a handful of default channels.
+    pub channel_mask: u16,
+    adr_ack_cnt: u16,
+    last_snr_db: i8,
+    last_rssi_dbm: i16,
+}
+
+impl AdrState {
+    pub const fn new() -> Self {
+        AdrState {
+            enabled: true,
+            data_rate: MIN_DATA_RATE,
+            tx_power_index: 0,
+            channel_mask: 0xFFFF,
+            adr_ack_cnt: 0,
+            last_snr_db: 0,
+            last_rssi_dbm: 0,
+        }
+    }
+
+    /// Record the radio-reported link quality of the most recent downlink.
+    pub fn record_downlink_quality(&mut self, snr_db: i8, rssi_dbm: i16) {
+        self.last_snr_db = snr_db;
+        self.last_rssi_dbm = rssi_dbm;
+    }
+
+    pub fn last_snr_db(&self) -> i8 {
+        self.last_snr_db
+    }
+
+    pub fn last_rssi_dbm(&self) -> i16 {
+        self.last_rssi_dbm
+    }
+
+    /// A downlink was received: the link is alive, so the back-off counter
+    /// resets.
+    pub fn on_downlink_received(&mut self) {
+        self.adr_ack_cnt = 0;
+    }
+
+    /// Whether the uplink currently being built should carry the ADRACKReq
+    /// bit, without touching ADR_ACK_CNT. Used when the frame is assembled,
+    /// ahead of `on_uplink_sent`'s accounting once the send is confirmed.
+    pub fn next_adr_ack_req(&self) -> bool {
+        self.enabled && self.adr_ack_cnt >= ADR_ACK_LIMIT
+    }
+
+    /// An uplink is about to go out. Bumps ADR_ACK_CNT, steps the data rate
+    /// down once ADR_ACK_DELAY has elapsed past ADR_ACK_LIMIT, and returns
+    /// whether this uplink should carry the ADRACKReq bit (the same answer
+    /// `next_adr_ack_req` gave before the bump).
+    pub fn on_uplink_sent(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let adr_ack_req = self.next_adr_ack_req();
+        self.adr_ack_cnt = self.adr_ack_cnt.saturating_add(1);
+
+        if self.adr_ack_cnt >= ADR_ACK_LIMIT + ADR_ACK_DELAY {
+            self.step_down();
+            // Give the network another full ADR_ACK_DELAY window at the new,
+            // more robust data rate before stepping down again.
+            self.adr_ack_cnt = ADR_ACK_LIMIT;
+        }
+
+        adr_ack_req
+    }
+
+    /// Step the data rate down one notch and re-enable every default
+    /// channel, per the spec's ADR back-off recovery procedure.
+    fn step_down(&mut self) {
+        if self.data_rate > MIN_DATA_RATE {
+            self.data_rate -= 1;
+            defmt::info!("ADR back-off: stepping down to DR{}", self.data_rate);
+        }
+        self.channel_mask = 0xFFFF;
+    }
+
+    /// Apply a network-issued `LinkADRReq`, reporting which parts were
+    /// accepted so the caller can build the `LinkADRAns` status bits.
+    pub fn apply_link_adr_req(
+        &mut self,
+        data_rate: Option<u8>,
+        tx_power_index: Option<u8>,
+        channel_mask: Option<u16>,
+    ) -> LinkAdrResult {
+        let data_rate_ok = match data_rate {
+            Some(dr) if dr <= MAX_DATA_RATE => {
+                self.data_rate = dr;
+                true
+            }
+            Some(_) => false,
+            None => true,
+        };
+
+        let power_ok = match tx_power_index {
+            Some(p) => {
+                self.tx_power_index = p;
+                true
+            }
+            None => true,
+        };
+
+        let channel_mask_ok = match channel_mask {
+            Some(mask) if mask != 0 => {
+                self.channel_mask = mask;
+                true
+            }
+            Some(_) => false,
+            None => true,
+        };
+
+        defmt::info!(
+            "LinkADRReq applied: DR{} pwr_idx={} mask={:016b}",
+            self.data_rate, self.tx_power_index, self.channel_mask
+        );
+
+        LinkAdrResult { power_ok, data_rate_ok, channel_mask_ok }
+    }
+}
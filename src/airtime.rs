@@ -0,0 +1,156 @@
+//! LoRa time-on-air computation and per-band duty-cycle / dwell-time
+//! enforcement.
+//!
+//! EU433 caps every sub-band at 1% duty cycle; some regions additionally
+//! cap the time-on-air of a single uplink (max dwell time). Both are spec
+//! requirements, not just good citizenship, so an uplink that would breach
+//! either is rejected here before it reaches the radio.
+
+/// LoRa PHY parameters needed to compute time-on-air for one uplink.
+#[derive(Clone, Copy, Debug)]
+pub struct PhyConfig {
+    /// Spreading factor, 7..=12.
+    pub sf: u8,
+    /// Signal bandwidth in Hz (e.g. 125_000).
+    pub bw_hz: u32,
+    /// Coding rate, 1..=4 for 4/5..4/8.
+    pub coding_rate: u8,
+    pub preamble_symbols: u16,
+    pub explicit_header: bool,
+    pub crc_on: bool,
+}
+
+impl PhyConfig {
+    /// Low-data-rate optimization (DE) is mandatory at SF11/SF12 on 125 kHz.
+    fn low_data_rate_optimize(&self) -> bool {
+        self.sf >= 11 && self.bw_hz <= 125_000
+    }
+}
+
+/// EU433 default join/fallback data rate: SF12, 125 kHz, CR 4/5.
+pub const EU433_DEFAULT_PHY: PhyConfig = PhyConfig {
+    sf: 12,
+    bw_hz: 125_000,
+    coding_rate: 1,
+    preamble_symbols: 8,
+    explicit_header: true,
+    crc_on: true,
+};
+
+/// EU433's data rates DR0..DR5 are all 125 kHz, stepping one spreading
+/// factor per index from SF12 (DR0) down to SF7 (DR5) — the same ladder
+/// `AdrState` drives via `LinkADRReq`/back-off.
+pub fn phy_config_for_data_rate(data_rate: u8) -> PhyConfig {
+    let sf = 12u8.saturating_sub(data_rate).max(7);
+    PhyConfig { sf, ..EU433_DEFAULT_PHY }
+}
+
+/// Symbol period, in microseconds, for one LoRa symbol: `2^SF / BW`.
+fn symbol_period_us(cfg: &PhyConfig) -> u32 {
+    ((1u64 << cfg.sf as u32) * 1_000_000 / cfg.bw_hz as u64) as u32
+}
+
+/// Time-on-air of an uplink carrying `payload_len` bytes, in microseconds.
+///
+/// Implements the standard Semtech formula:
+/// `Tsym = 2^SF / BW`
+/// `Tpreamble = (n_preamble + 4.25) * Tsym`
+/// `payloadSymbNb = 8 + max(ceil((8*PL - 4*SF + 28 + 16*CRC - 20*IH) / (4*(SF-2*DE))) * (CR+4), 0)`
+/// `Tpayload = payloadSymbNb * Tsym`
+pub fn time_on_air_us(payload_len: u8, cfg: &PhyConfig) -> u32 {
+    let tsym_us = symbol_period_us(cfg);
+
+    // (n_preamble + 4.25) * Tsym, done in quarters of a symbol to avoid floats.
+    let t_preamble_us = (cfg.preamble_symbols as u32 * 4 + 17) * tsym_us / 4;
+
+    let sf = cfg.sf as i32;
+    let de = if cfg.low_data_rate_optimize() { 1 } else { 0 };
+    let ih = if cfg.explicit_header { 0 } else { 1 };
+    let crc = if cfg.crc_on { 1 } else { 0 };
+
+    let numerator = 8 * payload_len as i32 - 4 * sf + 28 + 16 * crc - 20 * ih;
+    let denominator = 4 * (sf - 2 * de);
+
+    let extra_symbols = if numerator > 0 {
+        // ceil(numerator / denominator) for positive operands.
+        (numerator + denominator - 1) / denominator
+    } else {
+        0
+    };
+    let payload_symb_nb = 8 + (extra_symbols * (cfg.coding_rate as i32 + 4)).max(0) as u32;
+
+    let t_payload_us = payload_symb_nb * tsym_us;
+    t_preamble_us + t_payload_us
+}
+
+/// Time-on-air rounded up to whole milliseconds, as used for duty-cycle
+/// bookkeeping (which is tracked in ms, like the rest of the firmware's
+/// LPTIM-driven timing).
+pub fn time_on_air_ms(payload_len: u8, cfg: &PhyConfig) -> u32 {
+    (time_on_air_us(payload_len, cfg) + 999) / 1000
+}
+
+/// A region may cap the on-air time of a single uplink regardless of the
+/// duty-cycle budget (e.g. 400 ms dwell time in some AS923 variants). EU433
+/// has no dwell-time limit; `None` disables the check.
+pub const EU433_MAX_DWELL_TIME_MS: Option<u32> = None;
+
+#[derive(Debug)]
+pub enum AirtimeError {
+    /// This single uplink's time-on-air exceeds the region's dwell-time cap.
+    DwellTimeExceeded { toa_ms: u32, max_ms: u32 },
+    /// The sub-band's duty-cycle budget isn't free again until `retry_at_ms`.
+    DutyCycleBudgetExhausted { retry_at_ms: u32 },
+}
+
+/// Number of sub-bands tracked independently. EU433 has a single band for
+/// this device's channel plan; regions with per-sub-band limits (e.g. EU868)
+/// would use more.
+const NUM_BANDS: usize = 1;
+
+/// Tracks, per sub-band, the earliest time at which another uplink is
+/// allowed without exceeding the regulatory duty cycle.
+pub struct DutyCycleTracker {
+    /// Duty cycle budget expressed in permille (1% == 10).
+    duty_cycle_permille: [u32; NUM_BANDS],
+    /// Monotonic ms timestamp at which each band is free again.
+    free_at_ms: [u32; NUM_BANDS],
+}
+
+impl DutyCycleTracker {
+    pub const fn new_eu433() -> Self {
+        DutyCycleTracker {
+            duty_cycle_permille: [10; NUM_BANDS], // 1%
+            free_at_ms: [0; NUM_BANDS],
+        }
+    }
+
+    /// Check whether an uplink of `toa_ms` on `band` is allowed at `now_ms`,
+    /// enforcing both the dwell-time cap (if any) and the duty-cycle budget.
+    /// On success, reserves the band until its next legal transmission time.
+    pub fn reserve(
+        &mut self,
+        band: usize,
+        now_ms: u32,
+        toa_ms: u32,
+        max_dwell_ms: Option<u32>,
+    ) -> Result<(), AirtimeError> {
+        if let Some(max_ms) = max_dwell_ms {
+            if toa_ms > max_ms {
+                return Err(AirtimeError::DwellTimeExceeded { toa_ms, max_ms });
+            }
+        }
+
+        if now_ms < self.free_at_ms[band] {
+            return Err(AirtimeError::DutyCycleBudgetExhausted {
+                retry_at_ms: self.free_at_ms[band],
+            });
+        }
+
+        // Off-air time needed so that toa_ms / (toa_ms + off_ms) == duty cycle.
+        let permille = self.duty_cycle_permille[band];
+        let off_ms = toa_ms * (1000 - permille) / permille;
+        self.free_at_ms[band] = now_ms + toa_ms + off_ms;
+        Ok(())
+    }
+}
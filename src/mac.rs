@@ -0,0 +1,172 @@
+//! MAC command handling for the FOpts carried in uplinks/downlinks.
+//!
+//! `lorawan_response`'s `DownlinkReceived` arm used to just count the FOpts
+//! entries; this module actually parses them, answers the ones that require
+//! an answer, and lets the application request `LinkCheckReq`/`DeviceTimeReq`
+//! on a future uplink.
+
+use crate::adr::AdrState;
+use lorawan_encoding::maccommands::MacCommand;
+
+/// Result of the most recent `LinkCheckAns`, as reported by the network.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkCheck {
+    /// Demodulation margin in dB, as reported by the last gateway.
+    pub margin_db: u8,
+    /// Number of gateways that received the LinkCheckReq.
+    pub gateway_count: u8,
+}
+
+/// Result of the most recent `DeviceTimeAns`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceTime {
+    /// Seconds since the GPS epoch (1980-01-06T00:00:00Z).
+    pub seconds: u32,
+    /// Fractional second, in 1/256ths.
+    pub fraction: u8,
+}
+
+/// Maximum number of MAC command answers queued for the next uplink.
+/// Large enough for the handful of answers a single downlink can provoke
+/// (e.g. one LinkADRAns plus one RXParamSetupAns).
+const MAX_PENDING_ANSWERS: usize = 4;
+
+/// RX1 offset already in effect (no offset applied), used as the baseline
+/// `RXParamSetupReq` is checked against below.
+const DEFAULT_RX1_DR_OFFSET: u8 = 0;
+/// RX2 data rate already in effect: DR0 (SF12, 125 kHz), EU433's join-accept
+/// default (TS001 / RP002 EU433 regional parameters).
+const DEFAULT_RX2_DATA_RATE: u8 = 0;
+/// RX2 frequency already in effect, in Hz: EU433's default of 434.665 MHz.
+const DEFAULT_RX2_FREQUENCY_HZ: u32 = 434_665_000;
+
+/// Shared MAC-layer state: results from the last downlink, plus commands
+/// queued to go out (as requests or answers) on the next uplink's FOpts.
+pub struct MacState {
+    pub last_link_check: Option<LinkCheck>,
+    pub last_device_time: Option<DeviceTime>,
+    want_link_check_req: bool,
+    want_device_time_req: bool,
+    pending_answers: heapless::Vec<PendingAnswer, MAX_PENDING_ANSWERS>,
+}
+
+/// An answer to a network-initiated MAC command, queued until the next
+/// uplink serializes it into FOpts.
+#[derive(Clone, Copy, Debug)]
+pub enum PendingAnswer {
+    LinkAdrAns { power_ok: bool, dr_ok: bool, channel_mask_ok: bool },
+    RxParamSetupAns { rx1_dr_offset_ok: bool, rx2_dr_ok: bool, channel_ok: bool },
+}
+
+impl MacState {
+    pub const fn new() -> Self {
+        MacState {
+            last_link_check: None,
+            last_device_time: None,
+            want_link_check_req: false,
+            want_device_time_req: false,
+            pending_answers: heapless::Vec::new(),
+        }
+    }
+
+    /// Parse every FOpts entry in a downlink, updating link-check/device-time
+    /// state, applying ADR changes, and queuing an `*Ans` for anything that
+    /// demands one.
+    pub fn handle_downlink_fopts<'a>(&mut self, fopts: impl Iterator<Item = MacCommand<'a>>, adr: &mut AdrState) {
+        for cmd in fopts {
+            match cmd {
+                MacCommand::LinkCheckAns(ans) => {
+                    self.last_link_check = Some(LinkCheck {
+                        margin_db: ans.margin(),
+                        gateway_count: ans.gateway_count(),
+                    });
+                    defmt::info!("LinkCheckAns: margin={} gw_cnt={}", ans.margin(), ans.gateway_count());
+                }
+                MacCommand::DeviceTimeAns(ans) => {
+                    self.last_device_time = Some(DeviceTime {
+                        seconds: ans.seconds(),
+                        fraction: ans.nano_seconds(),
+                    });
+                    defmt::info!("DeviceTimeAns: seconds={}", ans.seconds());
+                }
+                MacCommand::LinkADRReq(req) => {
+                    let result = adr.apply_link_adr_req(
+                        Some(req.data_rate()),
+                        Some(req.tx_power()),
+                        Some(req.channel_mask().as_u16()),
+                    );
+                    let _ = self.pending_answers.push(PendingAnswer::LinkAdrAns {
+                        power_ok: result.power_ok,
+                        dr_ok: result.data_rate_ok,
+                        channel_mask_ok: result.channel_mask_ok,
+                    });
+                }
+                MacCommand::RXParamSetupReq(req) => {
+                    // We have no mechanism yet to actually reconfigure the
+                    // radio's RX1/RX2 windows, so only ack `true` for parts
+                    // of the request that match what's already in effect (a
+                    // no-op); anything that would require a real change is
+                    // honestly reported as not applied rather than
+                    // rubber-stamped.
+                    let rx1_dr_offset_ok = req.dr_offset() == DEFAULT_RX1_DR_OFFSET;
+                    let rx2_dr_ok = req.rx2_data_rate() == DEFAULT_RX2_DATA_RATE;
+                    let channel_ok = req.frequency().value() == DEFAULT_RX2_FREQUENCY_HZ;
+                    defmt::info!(
+                        "RXParamSetupReq: offset_ok={} rx2_dr_ok={} channel_ok={}",
+                        rx1_dr_offset_ok, rx2_dr_ok, channel_ok
+                    );
+                    let _ = self.pending_answers.push(PendingAnswer::RxParamSetupAns {
+                        rx1_dr_offset_ok,
+                        rx2_dr_ok,
+                        channel_ok,
+                    });
+                }
+                _ => {
+                    defmt::info!("Unhandled MAC command in FOpts");
+                }
+            }
+        }
+    }
+
+    /// Application-facing: ask for a `LinkCheckReq` on the next uplink.
+    pub fn request_link_check(&mut self) {
+        self.want_link_check_req = true;
+    }
+
+    /// Application-facing: ask for a `DeviceTimeReq` on the next uplink.
+    pub fn request_device_time(&mut self) {
+        self.want_device_time_req = true;
+    }
+
+    /// Drain everything that should go out on the next uplink's FOpts:
+    /// queued answers first, then any pending requests. Clears the queue; a
+    /// request that does not fit stays pending for the following uplink.
+    pub fn drain_pending_fopts(&mut self) -> heapless::Vec<MacCommand<'static>, MAX_PENDING_ANSWERS> {
+        let mut out = heapless::Vec::new();
+
+        for answer in self.pending_answers.drain(..) {
+            let cmd = match answer {
+                PendingAnswer::LinkAdrAns { power_ok, dr_ok, channel_mask_ok } => {
+                    MacCommand::LinkADRAns(lorawan_encoding::maccommands::build_link_adr_ans(
+                        power_ok, dr_ok, channel_mask_ok,
+                    ))
+                }
+                PendingAnswer::RxParamSetupAns { rx1_dr_offset_ok, rx2_dr_ok, channel_ok } => {
+                    MacCommand::RXParamSetupAns(lorawan_encoding::maccommands::build_rx_param_setup_ans(
+                        rx1_dr_offset_ok, rx2_dr_ok, channel_ok,
+                    ))
+                }
+            };
+            let _ = out.push(cmd);
+        }
+
+        if self.want_link_check_req && out.push(MacCommand::LinkCheckReq(Default::default())).is_ok() {
+            self.want_link_check_req = false;
+        }
+        if self.want_device_time_req && out.push(MacCommand::DeviceTimeReq(Default::default())).is_ok() {
+            self.want_device_time_req = false;
+        }
+
+        out
+    }
+}
@@ -0,0 +1,195 @@
+//! Non-volatile session persistence.
+//!
+//! The LoRaWAN session (DevAddr, session keys, region/channel state and frame
+//! counters) is written to a reserved flash page after every downlink and
+//! every few uplinks (see `persist_session` in `main`) so that `init` can restore an already-joined (ABP-style) session after a
+//! power cycle instead of forcing a full OTAA rejoin.
+
+use stm32wl_hal as hal;
+use hal::pac;
+
+/// Last page of the STM32WL's 256 KiB flash, reserved for session storage.
+/// The linker script must keep the application out of this page.
+const SESSION_PAGE_ADDR: u32 = 0x0803_F800;
+const SESSION_PAGE_NUM: u8 = 127;
+
+const MAGIC: u32 = 0x4C57_5353; // "LWSS"
+const VERSION: u8 = 2;
+
+/// Number of uplinks' worth of headroom added to the restored FCntUp so that
+/// a crash between "frame counter incremented" and "record flushed" can
+/// never cause a counter reuse (which the network would reject as a replay).
+const FCNT_UP_SAFETY_MARGIN: u32 = 16;
+
+/// Everything needed to resume a LoRaWAN session without rejoining.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SessionRecord {
+    pub dev_addr: [u8; 4],
+    pub nwk_skey: [u8; 16],
+    pub app_skey: [u8; 16],
+    pub channel_mask: [u8; 2],
+    pub fcnt_up: u32,
+    pub fcnt_down: u32,
+    /// ADR data rate in effect at the last write, so `AdrState` can resume
+    /// from it too instead of restarting ADR at its hardcoded default.
+    pub data_rate: u8,
+}
+
+impl SessionRecord {
+    const LEN: usize = 4 + 1 + 4 + 16 + 16 + 2 + 4 + 4 + 1 + 4; // magic+version+fields+crc
+
+    fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut buf = [0u8; Self::LEN];
+        let mut i = 0;
+        buf[i..i + 4].copy_from_slice(&MAGIC.to_le_bytes());
+        i += 4;
+        buf[i] = VERSION;
+        i += 1;
+        buf[i..i + 4].copy_from_slice(&self.dev_addr);
+        i += 4;
+        buf[i..i + 16].copy_from_slice(&self.nwk_skey);
+        i += 16;
+        buf[i..i + 16].copy_from_slice(&self.app_skey);
+        i += 16;
+        buf[i..i + 2].copy_from_slice(&self.channel_mask);
+        i += 2;
+        buf[i..i + 4].copy_from_slice(&self.fcnt_up.to_le_bytes());
+        i += 4;
+        buf[i..i + 4].copy_from_slice(&self.fcnt_down.to_le_bytes());
+        i += 4;
+        buf[i] = self.data_rate;
+        i += 1;
+        let crc = crc32(&buf[..i]);
+        buf[i..i + 4].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::LEN {
+            return None;
+        }
+        let magic = u32::from_le_bytes(buf[0..4].try_into().ok()?);
+        let version = buf[4];
+        if magic != MAGIC || version != VERSION {
+            return None;
+        }
+        let crc_stored = u32::from_le_bytes(buf[Self::LEN - 4..Self::LEN].try_into().ok()?);
+        if crc32(&buf[..Self::LEN - 4]) != crc_stored {
+            return None;
+        }
+
+        let mut dev_addr = [0u8; 4];
+        dev_addr.copy_from_slice(&buf[5..9]);
+        let mut nwk_skey = [0u8; 16];
+        nwk_skey.copy_from_slice(&buf[9..25]);
+        let mut app_skey = [0u8; 16];
+        app_skey.copy_from_slice(&buf[25..41]);
+        let mut channel_mask = [0u8; 2];
+        channel_mask.copy_from_slice(&buf[41..43]);
+        let fcnt_up = u32::from_le_bytes(buf[43..47].try_into().ok()?);
+        let fcnt_down = u32::from_le_bytes(buf[47..51].try_into().ok()?);
+        let data_rate = buf[51];
+
+        Some(SessionRecord {
+            dev_addr,
+            nwk_skey,
+            app_skey,
+            channel_mask,
+            fcnt_up,
+            fcnt_down,
+            data_rate,
+        })
+    }
+
+    /// FCntUp to resume transmitting from, bumped by a safety margin above
+    /// the last value that was durably persisted.
+    pub fn resume_fcnt_up(&self) -> u32 {
+        self.fcnt_up.saturating_add(FCNT_UP_SAFETY_MARGIN)
+    }
+}
+
+/// Restore the session record from flash, if one was ever written and it
+/// passes the magic/version/CRC check. Any failure is treated as "no valid
+/// session" so the caller falls back to a fresh join.
+pub fn load() -> Option<SessionRecord> {
+    let flash = unsafe { core::slice::from_raw_parts(SESSION_PAGE_ADDR as *const u8, SessionRecord::LEN) };
+    SessionRecord::from_bytes(flash)
+}
+
+/// Erase the session page and write `record` to it.
+///
+/// Must not be called from an ISR context: flash erase/program on the
+/// STM32WL stalls bus accesses and is only safe from the task priority this
+/// is driven at in `lorawan_response`.
+pub fn save(flash: &mut pac::FLASH, record: &SessionRecord) {
+    unlock(flash);
+    erase_page(flash, SESSION_PAGE_NUM);
+    program(flash, SESSION_PAGE_ADDR, &record.to_bytes());
+    lock(flash);
+}
+
+/// Invalidate the persisted session, forcing a rejoin on the next boot.
+pub fn invalidate(flash: &mut pac::FLASH) {
+    unlock(flash);
+    erase_page(flash, SESSION_PAGE_NUM);
+    lock(flash);
+}
+
+fn unlock(flash: &mut pac::FLASH) {
+    if flash.cr.read().lock().bit_is_set() {
+        flash.keyr.write(|w| unsafe { w.bits(0x4567_0123) });
+        flash.keyr.write(|w| unsafe { w.bits(0xCDEF_89AB) });
+    }
+}
+
+fn lock(flash: &mut pac::FLASH) {
+    flash.cr.modify(|_, w| w.lock().set_bit());
+}
+
+fn wait_ready(flash: &pac::FLASH) {
+    while flash.sr.read().bsy().bit_is_set() {}
+}
+
+fn erase_page(flash: &mut pac::FLASH, page: u8) {
+    wait_ready(flash);
+    flash.cr.modify(|_, w| unsafe { w.pnb().bits(page).per().set_bit() });
+    flash.cr.modify(|_, w| w.strt().set_bit());
+    wait_ready(flash);
+    flash.cr.modify(|_, w| w.per().clear_bit());
+}
+
+fn program(flash: &mut pac::FLASH, addr: u32, data: &[u8]) {
+    wait_ready(flash);
+    flash.cr.modify(|_, w| w.pg().set_bit());
+
+    for (i, word) in data.chunks(8).enumerate() {
+        let mut lo = [0u8; 4];
+        let mut hi = [0u8; 4];
+        lo[..word.len().min(4)].copy_from_slice(&word[..word.len().min(4)]);
+        if word.len() > 4 {
+            hi[..word.len() - 4].copy_from_slice(&word[4..]);
+        }
+        let dst = (addr + (i as u32) * 8) as *mut u32;
+        unsafe {
+            core::ptr::write_volatile(dst, u32::from_le_bytes(lo));
+            core::ptr::write_volatile(dst.add(1), u32::from_le_bytes(hi));
+        }
+        wait_ready(flash);
+    }
+
+    flash.cr.modify(|_, w| w.pg().clear_bit());
+}
+
+/// CRC-32 (IEEE 802.3), computed in software since the record is small and
+/// infrequently written.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
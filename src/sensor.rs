@@ -0,0 +1,219 @@
+//! Periodic sensor uplink: reads a BME280 (temperature/humidity/pressure)
+//! over I2C and encodes the result into a compact application payload.
+//!
+//! This is the only producer of `LorawanEvent::SendDataRequest` in the
+//! firmware, closing the loop from `JoinSuccess` to an actual metering
+//! uplink.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+const BME280_ADDR: u8 = 0x76;
+
+const REG_CALIB00: u8 = 0x88;
+const REG_CALIB26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_PRESS_MSB: u8 = 0xF7;
+
+/// `status` register bit 3: set while a conversion is in progress.
+const STATUS_MEASURING: u8 = 0b0000_1000;
+
+/// Upper bound on status-register polls in `read()`. The conversion takes
+/// well under 10ms at x1 oversampling, so this is generous headroom; it
+/// exists purely to bound the loop if the sensor never clears the bit (a
+/// flaky bus/sensor fault that still ACKs reads), rather than spinning this
+/// priority-2 task forever and starving the LoRaWAN stack at the same
+/// priority.
+const MAX_STATUS_POLLS: u8 = 50;
+
+/// Calibration coefficients read once at startup, per the BME280 datasheet
+/// (Bosch BST-BME280, section 4.2.2).
+struct Calibration {
+    dig_t1: u16,
+    dig_t2: i16,
+    dig_t3: i16,
+    dig_p1: u16,
+    dig_p2: i16,
+    dig_p3: i16,
+    dig_p4: i16,
+    dig_p5: i16,
+    dig_p6: i16,
+    dig_p7: i16,
+    dig_p8: i16,
+    dig_p9: i16,
+    dig_h1: u8,
+    dig_h2: i16,
+    dig_h3: u8,
+    dig_h4: i16,
+    dig_h5: i16,
+    dig_h6: i8,
+}
+
+pub struct Bme280<I2C> {
+    i2c: I2C,
+    address: u8,
+    calib: Calibration,
+}
+
+/// Error reading or configuring the sensor; the bus error itself is
+/// discarded since we have no recovery path finer than "skip this uplink
+/// cycle".
+#[derive(Debug)]
+pub struct SensorError;
+
+/// Decoded, compensated sensor reading.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reading {
+    /// Temperature in hundredths of a degree Celsius.
+    pub temperature_centi_c: i16,
+    /// Relative humidity in hundredths of a percent.
+    pub humidity_centi_pct: u16,
+    /// Pressure in Pascals.
+    pub pressure_pa: u32,
+}
+
+impl<I2C, E> Bme280<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Read calibration data and put the sensor into forced mode with
+    /// oversampling x1 on all three channels (plenty for a periodic
+    /// metering uplink, and keeps the conversion time short).
+    pub fn new(mut i2c: I2C) -> Result<Self, SensorError> {
+        let mut calib00 = [0u8; 26];
+        i2c.write_read(BME280_ADDR, &[REG_CALIB00], &mut calib00).map_err(|_| SensorError)?;
+        let mut calib26 = [0u8; 7];
+        i2c.write_read(BME280_ADDR, &[REG_CALIB26], &mut calib26).map_err(|_| SensorError)?;
+
+        let calib = Calibration {
+            dig_t1: u16::from_le_bytes([calib00[0], calib00[1]]),
+            dig_t2: i16::from_le_bytes([calib00[2], calib00[3]]),
+            dig_t3: i16::from_le_bytes([calib00[4], calib00[5]]),
+            dig_p1: u16::from_le_bytes([calib00[6], calib00[7]]),
+            dig_p2: i16::from_le_bytes([calib00[8], calib00[9]]),
+            dig_p3: i16::from_le_bytes([calib00[10], calib00[11]]),
+            dig_p4: i16::from_le_bytes([calib00[12], calib00[13]]),
+            dig_p5: i16::from_le_bytes([calib00[14], calib00[15]]),
+            dig_p6: i16::from_le_bytes([calib00[16], calib00[17]]),
+            dig_p7: i16::from_le_bytes([calib00[18], calib00[19]]),
+            dig_p8: i16::from_le_bytes([calib00[20], calib00[21]]),
+            dig_p9: i16::from_le_bytes([calib00[22], calib00[23]]),
+            dig_h1: calib00[25],
+            dig_h2: i16::from_le_bytes([calib26[0], calib26[1]]),
+            dig_h3: calib26[2],
+            dig_h4: ((calib26[3] as i16) << 4) | (calib26[4] as i16 & 0x0F),
+            dig_h5: ((calib26[5] as i16) << 4) | ((calib26[4] as i16) >> 4),
+            dig_h6: calib26[6] as i8,
+        };
+
+        i2c.write(BME280_ADDR, &[REG_CTRL_HUM, 0x01]).map_err(|_| SensorError)?; // humidity oversampling x1
+        i2c.write(BME280_ADDR, &[REG_CTRL_MEAS, 0b001_001_01]).map_err(|_| SensorError)?; // temp/press osrs x1, forced mode
+
+        Ok(Bme280 { i2c, address: BME280_ADDR, calib })
+    }
+
+    /// Trigger a forced-mode conversion and read back compensated values
+    /// once it completes. The conversion takes well under 10ms at x1
+    /// oversampling, so we poll the status register rather than pull in a
+    /// delay peripheral for it.
+    pub fn read(&mut self) -> Result<Reading, SensorError> {
+        self.i2c
+            .write(self.address, &[REG_CTRL_MEAS, 0b001_001_01])
+            .map_err(|_| SensorError)?;
+
+        let mut status = [0u8; 1];
+        let mut polls_left = MAX_STATUS_POLLS;
+        loop {
+            self.i2c
+                .write_read(self.address, &[REG_STATUS], &mut status)
+                .map_err(|_| SensorError)?;
+            if status[0] & STATUS_MEASURING == 0 {
+                break;
+            }
+            polls_left -= 1;
+            if polls_left == 0 {
+                return Err(SensorError);
+            }
+        }
+
+        let mut raw = [0u8; 8];
+        self.i2c
+            .write_read(self.address, &[REG_PRESS_MSB], &mut raw)
+            .map_err(|_| SensorError)?;
+
+        let adc_p: i32 = ((raw[0] as i32) << 12) | ((raw[1] as i32) << 4) | (raw[2] as i32 >> 4);
+        let adc_t: i32 = ((raw[3] as i32) << 12) | ((raw[4] as i32) << 4) | (raw[5] as i32 >> 4);
+        let adc_h: i32 = ((raw[6] as i32) << 8) | (raw[7] as i32);
+
+        let (temp_centi_c, t_fine) = self.compensate_temperature(adc_t);
+        let pressure_pa = self.compensate_pressure(adc_p, t_fine);
+        let humidity_centi_pct = self.compensate_humidity(adc_h, t_fine);
+
+        Ok(Reading {
+            temperature_centi_c: temp_centi_c,
+            humidity_centi_pct,
+            pressure_pa,
+        })
+    }
+
+    /// Returns (temperature in 0.01 degC, t_fine for the pressure/humidity
+    /// compensation that follows), per datasheet section 4.2.3.
+    fn compensate_temperature(&self, adc_t: i32) -> (i16, i32) {
+        let c = &self.calib;
+        let var1 = (((adc_t >> 3) - ((c.dig_t1 as i32) << 1)) * (c.dig_t2 as i32)) >> 11;
+        let var2 = (((((adc_t >> 4) - (c.dig_t1 as i32)) * ((adc_t >> 4) - (c.dig_t1 as i32))) >> 12)
+            * (c.dig_t3 as i32))
+            >> 14;
+        let t_fine = var1 + var2;
+        let temp_centi_c = ((t_fine * 5 + 128) >> 8) as i16;
+        (temp_centi_c, t_fine)
+    }
+
+    fn compensate_pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let c = &self.calib;
+        let mut var1: i64 = (t_fine as i64) - 128000;
+        let mut var2: i64 = var1 * var1 * (c.dig_p6 as i64);
+        var2 += (var1 * (c.dig_p5 as i64)) << 17;
+        var2 += (c.dig_p4 as i64) << 35;
+        var1 = ((var1 * var1 * (c.dig_p3 as i64)) >> 8) + ((var1 * (c.dig_p2 as i64)) << 12);
+        var1 = (((1i64 << 47) + var1) * (c.dig_p1 as i64)) >> 33;
+        if var1 == 0 {
+            return 0;
+        }
+        let mut p: i64 = 1048576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = ((c.dig_p9 as i64) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = ((c.dig_p8 as i64) * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + ((c.dig_p7 as i64) << 4);
+        (p / 256) as u32
+    }
+
+    fn compensate_humidity(&self, adc_h: i32, t_fine: i32) -> u16 {
+        let c = &self.calib;
+        let mut v_x1: i32 = t_fine - 76800;
+        v_x1 = ((((adc_h << 14) - ((c.dig_h4 as i32) << 20) - ((c.dig_h5 as i32) * v_x1) + 16384) >> 15)
+            * (((((((v_x1 * (c.dig_h6 as i32)) >> 10) * (((v_x1 * (c.dig_h3 as i32)) >> 11) + 32768)) >> 10)
+                + 2097152)
+                * (c.dig_h2 as i32)
+                + 8192)
+                >> 14));
+        v_x1 -= ((((v_x1 >> 15) * (v_x1 >> 15)) >> 7) * (c.dig_h1 as i32)) >> 4;
+        let v_x1 = v_x1.clamp(0, 419_430_400);
+        // v_x1 >> 12 is %RH in Q22.10 fixed point; convert to hundredths of a percent.
+        ((v_x1 >> 12) * 100 / 1024) as u16
+    }
+}
+
+/// Application FPort used for periodic sensor uplinks.
+pub const SENSOR_FPORT: u8 = 2;
+
+/// Encode a reading into the compact wire payload: temperature (i16 LE,
+/// 0.01 degC), humidity (u16 LE, 0.01 %RH), pressure (u32 LE, Pa).
+pub fn encode_payload(reading: &Reading) -> [u8; 8] {
+    let mut buf = [0u8; 8];
+    buf[0..2].copy_from_slice(&reading.temperature_centi_c.to_le_bytes());
+    buf[2..4].copy_from_slice(&reading.humidity_centi_pct.to_le_bytes());
+    buf[4..8].copy_from_slice(&reading.pressure_pa.to_le_bytes());
+    buf
+}